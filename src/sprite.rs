@@ -1,17 +1,238 @@
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::mem;
 use std::rc::Rc;
 
+use serde::Deserialize;
 use uuid::Uuid;
 
-use graphics::math::{Matrix2d, Scalar, Vec2d};
+use graphics::math::{identity, multiply, transform_pos, Matrix2d, Scalar, Vec2d};
 use graphics::types::SourceRectangle;
-use graphics::{self, Graphics, ImageSize};
+use graphics::{self, Graphics, ImageSize, Transformed};
+
+/// Invert a 2x3 affine transform (the format `graphics::math::Matrix2d` uses
+/// for its implicit `[0, 0, 1]` row), for mapping a point from world space
+/// back into a sprite's local space.
+fn invert_affine(m: Matrix2d) -> Matrix2d {
+    let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+    let inv_det = 1.0 / det;
+    [
+        [m[1][1] * inv_det, -m[0][1] * inv_det, (m[0][1] * m[1][2] - m[1][1] * m[0][2]) * inv_det],
+        [-m[1][0] * inv_det, m[0][0] * inv_det, (m[1][0] * m[0][2] - m[0][0] * m[1][2]) * inv_det],
+    ]
+}
+
+/// A transition consulted when a section's playback reaches its top
+/// (`on_enter`) or bottom (`on_end`) frame.
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+pub enum Transition {
+    /// As `on_end`, hold on the current frame; as `on_enter`, play the
+    /// section from its first frame.
+    Stop,
+    /// Restart the section from its first frame.
+    Loop,
+    /// Play a different named section.
+    Goto(String),
+}
 
 #[derive(Clone)]
-pub struct FrameSet {
-    pub repeat: bool,
-    pub frame_time: f64,
+pub struct FrameSet<I: ImageSize> {
+    /// How long each frame in `source` is displayed for, in seconds.
+    pub durations: Vec<f64>,
     pub source: Vec<SourceRectangle>,
+    /// Per-frame texture override, used by framesets whose frames are each a
+    /// separate image rather than regions of the sprite's own texture.
+    /// `None` at an index means "use the sprite's own texture".
+    pub textures: Vec<Option<Rc<I>>>,
+    /// Transition consulted when this section is entered via `play`.
+    pub on_enter: Transition,
+    /// Transition consulted when `frame_idx` reaches the final frame.
+    pub on_end: Transition,
+}
+
+/// The blend mode used to composite a sprite onto whatever is already drawn,
+/// mirroring the `blendfunc` field carried by ANM sprites.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BlendMode {
+    /// Standard alpha compositing.
+    Alpha,
+    /// Additive blending, useful for glows and lasers.
+    Add,
+    /// Multiplicative blending, useful for tinting/shadowing.
+    Multiply,
+    /// Inverts the destination color.
+    Invert,
+}
+
+impl BlendMode {
+    /// The `DrawState` that implements this blend mode.
+    ///
+    /// Built from `DrawState::default()` in every case (rather than mixing
+    /// in `new_alpha()`, which doesn't have `new_additive`/`new_multiply`/
+    /// `new_invert` counterparts), so all four variants share the same
+    /// scissor/stencil defaults and differ only in `blend`.
+    fn draw_state(&self) -> graphics::DrawState {
+        let blend = match *self {
+            BlendMode::Alpha => graphics::draw_state::Blend::Alpha,
+            BlendMode::Add => graphics::draw_state::Blend::Add,
+            BlendMode::Multiply => graphics::draw_state::Blend::Multiply,
+            BlendMode::Invert => graphics::draw_state::Blend::Invert,
+        };
+        graphics::DrawState::default().blend(blend)
+    }
+}
+
+/// An easing formula used by the tweening interpolators.
+///
+/// `f(t)` maps a normalized time `t` in `[0, 1]` to an eased progress `u`,
+/// also in `[0, 1]`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Formula {
+    /// `f(t) = t`
+    Linear,
+    /// `f(t) = t * t`
+    EaseIn,
+    /// `f(t) = 1 - (1 - t)^2`
+    EaseOut,
+    /// `f(t) = t < 0.5 ? 2t^2 : 1 - 2(1 - t)^2`
+    EaseInOut,
+}
+
+impl Formula {
+    /// Apply the formula to a normalized time `t` in `[0, 1]`.
+    pub fn apply(&self, t: f64) -> f64 {
+        match *self {
+            Formula::Linear => t,
+            Formula::EaseIn => t * t,
+            Formula::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Formula::EaseInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - 2.0 * (1.0 - t) * (1.0 - t)
+                }
+            }
+        }
+    }
+}
+
+/// Interpolates a single component between `start_value` and `end_value`.
+#[derive(Clone)]
+pub struct Interpolator1 {
+    pub start_value: f64,
+    pub end_value: f64,
+    pub duration: f64,
+    pub formula: Formula,
+    elapsed: f64,
+}
+
+impl Interpolator1 {
+    /// Create a new interpolator
+    pub fn new(start_value: f64, end_value: f64, duration: f64, formula: Formula) -> Interpolator1 {
+        Interpolator1 {
+            start_value: start_value,
+            end_value: end_value,
+            duration: duration,
+            formula: formula,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Advance the interpolator by `dt` seconds.
+    ///
+    /// Returns the current value and whether the interpolation has finished.
+    pub fn update(&mut self, dt: f64) -> (f64, bool) {
+        self.elapsed += dt;
+        let t = (self.elapsed / self.duration).max(0.0).min(1.0);
+        let u = self.formula.apply(t);
+        let value = self.start_value + (self.end_value - self.start_value) * u;
+        (value, t >= 1.0)
+    }
+}
+
+/// Interpolates two components (e.g. position or scale) in lock-step.
+#[derive(Clone)]
+pub struct Interpolator2 {
+    pub start_value: [f64; 2],
+    pub end_value: [f64; 2],
+    pub duration: f64,
+    pub formula: Formula,
+    elapsed: f64,
+}
+
+impl Interpolator2 {
+    /// Create a new interpolator
+    pub fn new(
+        start_value: [f64; 2],
+        end_value: [f64; 2],
+        duration: f64,
+        formula: Formula,
+    ) -> Interpolator2 {
+        Interpolator2 {
+            start_value: start_value,
+            end_value: end_value,
+            duration: duration,
+            formula: formula,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Advance the interpolator by `dt` seconds.
+    ///
+    /// Returns the current value and whether the interpolation has finished.
+    pub fn update(&mut self, dt: f64) -> ([f64; 2], bool) {
+        self.elapsed += dt;
+        let t = (self.elapsed / self.duration).max(0.0).min(1.0);
+        let u = self.formula.apply(t);
+        let value = [
+            self.start_value[0] + (self.end_value[0] - self.start_value[0]) * u,
+            self.start_value[1] + (self.end_value[1] - self.start_value[1]) * u,
+        ];
+        (value, t >= 1.0)
+    }
+}
+
+/// Interpolates three components (e.g. color) in lock-step.
+#[derive(Clone)]
+pub struct Interpolator3 {
+    pub start_value: [f64; 3],
+    pub end_value: [f64; 3],
+    pub duration: f64,
+    pub formula: Formula,
+    elapsed: f64,
+}
+
+impl Interpolator3 {
+    /// Create a new interpolator
+    pub fn new(
+        start_value: [f64; 3],
+        end_value: [f64; 3],
+        duration: f64,
+        formula: Formula,
+    ) -> Interpolator3 {
+        Interpolator3 {
+            start_value: start_value,
+            end_value: end_value,
+            duration: duration,
+            formula: formula,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Advance the interpolator by `dt` seconds.
+    ///
+    /// Returns the current value and whether the interpolation has finished.
+    pub fn update(&mut self, dt: f64) -> ([f64; 3], bool) {
+        self.elapsed += dt;
+        let t = (self.elapsed / self.duration).max(0.0).min(1.0);
+        let u = self.formula.apply(t);
+        let value = [
+            self.start_value[0] + (self.end_value[0] - self.start_value[0]) * u,
+            self.start_value[1] + (self.end_value[1] - self.start_value[1]) * u,
+            self.start_value[2] + (self.end_value[2] - self.start_value[2]) * u,
+        ];
+        (value, t >= 1.0)
+    }
 }
 
 /// A sprite is a texture with some properties.
@@ -25,11 +246,16 @@ pub struct Sprite<I: ImageSize> {
     position: Vec2d,
     rotation: Scalar,
     scale: Vec2d,
+    skew: Vec2d,
     color: [f32; 3],
 
     flip_x: bool,
     flip_y: bool,
 
+    /// Cached local transform (position/rotation/scale/skew combined),
+    /// invalidated by their setters and recomputed lazily.
+    local_transform: Cell<Option<Matrix2d>>,
+
     opacity: f32,
 
     children: Vec<Sprite<I>>,
@@ -38,11 +264,20 @@ pub struct Sprite<I: ImageSize> {
     src_rect: Option<SourceRectangle>,
     texture: Rc<I>,
 
-    frames: Option<FrameSet>,
-    frames_followup: Option<String>,
-    frame_sets: HashMap<String, FrameSet>,
+    frames: Option<FrameSet<I>>,
+    frame_sets: HashMap<String, FrameSet<I>>,
     frame_idx: usize,
     frame_delta: f64,
+    frame_hit_marks: HashSet<usize>,
+    frame_hit_queue: Vec<usize>,
+
+    tween_position: Option<Interpolator2>,
+    tween_scale: Option<Interpolator2>,
+    tween_rotation: Option<Interpolator1>,
+    tween_color: Option<Interpolator3>,
+    tween_opacity: Option<Interpolator1>,
+
+    blend: Option<BlendMode>,
 }
 
 impl<I: ImageSize> Sprite<I> {
@@ -58,22 +293,34 @@ impl<I: ImageSize> Sprite<I> {
             position: [0.0, 0.0],
             rotation: 0.0,
             scale: [1.0, 1.0],
+            skew: [0.0, 0.0],
             color: [1.0, 1.0, 1.0],
 
             flip_x: false,
             flip_y: false,
 
+            local_transform: Cell::new(None),
+
             opacity: 1.0,
 
             texture: texture,
             src_rect: None,
 
             frames: None,
-            frames_followup: None,
             frame_idx: 0,
             frame_delta: 0.0,
+            frame_hit_marks: HashSet::new(),
+            frame_hit_queue: Vec::new(),
             frame_sets: HashMap::new(),
 
+            tween_position: None,
+            tween_scale: None,
+            tween_rotation: None,
+            tween_color: None,
+            tween_opacity: None,
+
+            blend: None,
+
             children: Vec::new(),
             children_index: HashMap::new(),
         }
@@ -91,19 +338,31 @@ impl<I: ImageSize> Sprite<I> {
             position: [0.0, 0.0],
             rotation: 0.0,
             scale: [1.0, 1.0],
+            skew: [0.0, 0.0],
             color: [1.0, 1.0, 1.0],
 
             flip_x: false,
             flip_y: false,
 
+            local_transform: Cell::new(None),
+
             opacity: 1.0,
 
             frames: None,
-            frames_followup: None,
             frame_idx: 0,
             frame_delta: 0.0,
+            frame_hit_marks: HashSet::new(),
+            frame_hit_queue: Vec::new(),
             frame_sets: HashMap::new(),
 
+            tween_position: None,
+            tween_scale: None,
+            tween_rotation: None,
+            tween_color: None,
+            tween_opacity: None,
+
+            blend: None,
+
             texture: texture,
             src_rect: From::from(src_rect),
 
@@ -152,6 +411,7 @@ impl<I: ImageSize> Sprite<I> {
     #[inline(always)]
     pub fn set_position(&mut self, x: Scalar, y: Scalar) {
         self.position = [x, y];
+        self.local_transform.set(None);
     }
 
     /// Set the sprite's draw color (tint)
@@ -176,6 +436,7 @@ impl<I: ImageSize> Sprite<I> {
     #[inline(always)]
     pub fn set_rotation(&mut self, deg: Scalar) {
         self.rotation = deg;
+        self.local_transform.set(None);
     }
 
     /// Get the sprite's scale
@@ -188,6 +449,63 @@ impl<I: ImageSize> Sprite<I> {
     #[inline(always)]
     pub fn set_scale(&mut self, sx: Scalar, sy: Scalar) {
         self.scale = [sx, sy];
+        self.local_transform.set(None);
+    }
+
+    /// Get the sprite's skew (horizontal, vertical)
+    #[inline(always)]
+    pub fn get_skew(&self) -> (Scalar, Scalar) {
+        (self.skew[0], self.skew[1])
+    }
+
+    /// Set the sprite's skew (horizontal, vertical), shearing the sprite
+    #[inline(always)]
+    pub fn set_skew(&mut self, sx: Scalar, sy: Scalar) {
+        self.skew = [sx, sy];
+        self.local_transform.set(None);
+    }
+
+    /// The sprite's transform relative to its parent (position, rotation,
+    /// scale and skew combined), recomputed lazily and cached until one of
+    /// those setters invalidates it.
+    fn local_transform(&self) -> Matrix2d {
+        if let Some(cached) = self.local_transform.get() {
+            return cached;
+        }
+        let transform = identity()
+            .trans(self.position[0], self.position[1])
+            .rot_deg(self.rotation)
+            .scale(self.scale[0], self.scale[1])
+            .shear([self.skew[0], self.skew[1]]);
+        self.local_transform.set(Some(transform));
+        transform
+    }
+
+    /// Compute the world transform of the child identified by `id`, given
+    /// the transform of this sprite's parent (pass `identity()` if this
+    /// sprite is the root of the tree) — the same convention `draw` uses
+    /// for its starting transform.
+    ///
+    /// This lets callers map a child's local coordinates into screen space
+    /// for picking or attaching effects.
+    ///
+    /// Only each node's *local* transform is cached (see `local_transform`);
+    /// this still walks from `parent_transform` down to `id` on every call.
+    /// A child holds no back-reference to its parent, so there's nowhere to
+    /// invalidate a cached *world* transform when an ancestor's setter
+    /// changes its local one — caching per-node local transforms gets the
+    /// same trig/scale work off the hot path without that problem.
+    pub fn get_world_transform(&self, id: Uuid, parent_transform: Matrix2d) -> Option<Matrix2d> {
+        let transformed = multiply(parent_transform, self.local_transform());
+        if self.id == id {
+            return Some(transformed);
+        }
+        for child in &self.children {
+            if let Some(found) = child.get_world_transform(id, transformed) {
+                return Some(found);
+            }
+        }
+        None
     }
 
     /// Whether or not the sprite is flipped horizontally.
@@ -242,6 +560,88 @@ impl<I: ImageSize> Sprite<I> {
         self.opacity = opacity;
     }
 
+    /// Get the sprite's own blend mode
+    ///
+    /// `None` means the sprite inherits the blend mode of its nearest
+    /// ancestor that has one set, falling back to normal alpha blending.
+    #[inline(always)]
+    pub fn get_blend(&self) -> Option<BlendMode> {
+        self.blend
+    }
+
+    /// Set the sprite's own blend mode, overriding whatever it would
+    /// otherwise inherit from its parent. Pass `None` to go back to
+    /// inheriting.
+    #[inline(always)]
+    pub fn set_blend(&mut self, blend: Option<BlendMode>) {
+        self.blend = blend;
+    }
+
+    /// Queue a frame-hit notification whenever `frame_idx` becomes the
+    /// active frame (e.g. to trigger a sound or spawn on an "impact" frame).
+    pub fn on_frame_hit(&mut self, frame_idx: usize) {
+        self.frame_hit_marks.insert(frame_idx);
+    }
+
+    /// Stop queuing frame-hit notifications for `frame_idx`.
+    pub fn off_frame_hit(&mut self, frame_idx: usize) {
+        self.frame_hit_marks.remove(&frame_idx);
+    }
+
+    /// Drain the frame-hit notifications queued since the last call.
+    pub fn drain_frame_hits(&mut self) -> Vec<usize> {
+        mem::replace(&mut self.frame_hit_queue, Vec::new())
+    }
+
+    /// Tween the sprite's position to `(x, y)` over `duration` seconds, using `formula`.
+    pub fn tween_position_to(&mut self, x: Scalar, y: Scalar, duration: f64, formula: Formula) {
+        self.tween_position = Some(Interpolator2::new(
+            [self.position[0], self.position[1]],
+            [x, y],
+            duration,
+            formula,
+        ));
+    }
+
+    /// Tween the sprite's scale to `(sx, sy)` over `duration` seconds, using `formula`.
+    pub fn tween_scale_to(&mut self, sx: Scalar, sy: Scalar, duration: f64, formula: Formula) {
+        self.tween_scale = Some(Interpolator2::new(
+            [self.scale[0], self.scale[1]],
+            [sx, sy],
+            duration,
+            formula,
+        ));
+    }
+
+    /// Tween the sprite's rotation (in degree) to `deg` over `duration` seconds, using `formula`.
+    pub fn tween_rotation_to(&mut self, deg: Scalar, duration: f64, formula: Formula) {
+        self.tween_rotation = Some(Interpolator1::new(self.rotation, deg, duration, formula));
+    }
+
+    /// Tween the sprite's draw color (tint) to `(r, g, b)` over `duration` seconds, using `formula`.
+    pub fn tween_color_to(&mut self, r: f32, g: f32, b: f32, duration: f64, formula: Formula) {
+        self.tween_color = Some(Interpolator3::new(
+            [
+                self.color[0] as f64,
+                self.color[1] as f64,
+                self.color[2] as f64,
+            ],
+            [r as f64, g as f64, b as f64],
+            duration,
+            formula,
+        ));
+    }
+
+    /// Tween the sprite's opacity to `opacity` over `duration` seconds, using `formula`.
+    pub fn tween_opacity_to(&mut self, opacity: f32, duration: f64, formula: Formula) {
+        self.tween_opacity = Some(Interpolator1::new(
+            self.opacity as f64,
+            opacity as f64,
+            duration,
+            formula,
+        ));
+    }
+
     /// Get the sprite's source rectangle
     #[inline(always)]
     pub fn get_src_rect(&self) -> Option<SourceRectangle> {
@@ -266,6 +666,59 @@ impl<I: ImageSize> Sprite<I> {
         self.texture = texture;
     }
 
+    /// The current frame's source rectangle and the anchor offset derived
+    /// from it, shared by the draw and hit-test paths.
+    fn frame_geometry(&self) -> (SourceRectangle, Vec2d) {
+        let (tex_w, tex_h) = self.texture.get_size();
+        let source_rectangle = match self.frames {
+            None => self
+                .src_rect
+                .unwrap_or([0.0, 0.0, tex_w as f64, tex_h as f64]),
+            Some(ref frame) => frame.source[self.frame_idx],
+        };
+        let anchor = [
+            self.anchor[0] * source_rectangle[2],
+            self.anchor[1] * source_rectangle[3],
+        ];
+        (source_rectangle, anchor)
+    }
+
+    /// Apply this sprite's `flip_x`/`flip_y` to `transform`, mirroring the
+    /// anchor-relative rect the same way for drawing and hit-testing.
+    fn apply_flip(
+        &self,
+        transform: Matrix2d,
+        source_rectangle: SourceRectangle,
+        anchor: Vec2d,
+    ) -> Matrix2d {
+        let mut model = transform;
+        if self.flip_x {
+            model = model
+                .trans(source_rectangle[2] - 2.0 * anchor[0], 0.0)
+                .flip_h();
+        }
+        if self.flip_y {
+            model = model
+                .trans(0.0, source_rectangle[3] - 2.0 * anchor[1])
+                .flip_v();
+        }
+        model
+    }
+
+    /// Get the texture to draw for the current frame, honoring a frameset's
+    /// per-frame texture override if one is set.
+    fn frame_texture(&self) -> &I {
+        match self.frames {
+            Some(ref frame) => frame
+                .textures
+                .get(self.frame_idx)
+                .and_then(|texture| texture.as_ref())
+                .map(|texture| &**texture)
+                .unwrap_or(&*self.texture),
+            None => &*self.texture,
+        }
+    }
+
     /// Add a sprite as the child of this sprite, return the added sprite's id.
     pub fn add_child(&mut self, sprite: Sprite<I>) -> Uuid {
         let id = sprite.id();
@@ -332,47 +785,27 @@ impl<I: ImageSize> Sprite<I> {
 
     /// Draw this sprite and its children
     pub fn draw<B: Graphics<Texture = I>>(&self, t: Matrix2d, b: &mut B) {
-        use graphics::*;
+        self.draw_blended(t, b, None);
+    }
 
+    fn draw_blended<B: Graphics<Texture = I>>(
+        &self,
+        t: Matrix2d,
+        b: &mut B,
+        inherited_blend: Option<BlendMode>,
+    ) {
         if !self.visible {
             return;
         }
 
-        let (tex_w, tex_h) = self.texture.get_size();
-        let tex_w = tex_w as f64;
-        let tex_h = tex_h as f64;
-        let source_rectangle = match self.frames {
-            None => self.src_rect.unwrap_or({
-                let (w, h) = (tex_w, tex_h);
-                [0.0, 0.0, w as f64, h as f64]
-            }),
-            Some(ref frame) => frame.source[self.frame_idx],
-        };
-        let anchor = [
-            self.anchor[0] * source_rectangle[2],
-            self.anchor[1] * source_rectangle[3],
-        ];
-
-        let transformed = t
-            .trans(self.position[0], self.position[1])
-            .rot_deg(self.rotation)
-            .scale(self.scale[0], self.scale[1]);
-
-        let mut model = transformed;
-
-        if self.flip_x {
-            model = model
-                .trans(source_rectangle[2] - 2.0 * anchor[0], 0.0)
-                .flip_h();
-        }
+        let (source_rectangle, anchor) = self.frame_geometry();
+        let texture: &I = self.frame_texture();
 
-        if self.flip_y {
-            model = model
-                .trans(0.0, source_rectangle[3] - 2.0 * anchor[1])
-                .flip_v();
-        }
+        let transformed = multiply(t, self.local_transform());
+        let model = self.apply_flip(transformed, source_rectangle, anchor);
 
-        let ref draw_state: graphics::DrawState = Default::default();
+        let blend = self.blend.or(inherited_blend);
+        let draw_state = blend.map(|mode| mode.draw_state()).unwrap_or_default();
 
         // for debug: bounding_box
         //model.rgb(1.0, 0.0, 0.0).draw(b);
@@ -386,59 +819,40 @@ impl<I: ImageSize> Sprite<I> {
                 source_rectangle[3],
             ])
             .maybe_src_rect(self.src_rect) // FIXME: check if this affects frame sets
-            .draw(&*self.texture, draw_state, model, b);
+            .draw(texture, &draw_state, model, b);
 
         // for debug: anchor point
         //c.trans(self.position[0], self.position[1]).rect(-5.0, -5.0, 10.0, 10.0).rgb(0.0, 0.0, 1.0).draw(b);
 
         for child in &self.children {
-            child.draw(transformed, b);
+            child.draw_blended(transformed, b, blend);
         }
     }
 
     /// Draw this sprite and its children with color
     pub fn draw_tinted<B: Graphics<Texture = I>>(&self, t: Matrix2d, b: &mut B, c: [f32; 3]) {
-        use graphics::*;
+        self.draw_tinted_blended(t, b, c, None);
+    }
 
+    fn draw_tinted_blended<B: Graphics<Texture = I>>(
+        &self,
+        t: Matrix2d,
+        b: &mut B,
+        c: [f32; 3],
+        inherited_blend: Option<BlendMode>,
+    ) {
         if !self.visible {
             return;
         }
 
-        let (tex_w, tex_h) = self.texture.get_size();
-        let tex_w = tex_w as f64;
-        let tex_h = tex_h as f64;
-        let source_rectangle = match self.frames {
-            None => self.src_rect.unwrap_or({
-                let (w, h) = (tex_w, tex_h);
-                [0.0, 0.0, w as f64, h as f64]
-            }),
-            Some(ref frame) => frame.source[self.frame_idx],
-        };
-        let anchor = [
-            self.anchor[0] * source_rectangle[2],
-            self.anchor[1] * source_rectangle[3],
-        ];
-
-        let transformed = t
-            .trans(self.position[0], self.position[1])
-            .rot_deg(self.rotation)
-            .scale(self.scale[0], self.scale[1]);
-
-        let mut model = transformed;
-
-        if self.flip_x {
-            model = model
-                .trans(source_rectangle[2] - 2.0 * anchor[0], 0.0)
-                .flip_h();
-        }
+        let (source_rectangle, anchor) = self.frame_geometry();
+        let texture: &I = self.frame_texture();
 
-        if self.flip_y {
-            model = model
-                .trans(0.0, source_rectangle[3] - 2.0 * anchor[1])
-                .flip_v();
-        }
+        let transformed = multiply(t, self.local_transform());
+        let model = self.apply_flip(transformed, source_rectangle, anchor);
 
-        let ref draw_state: graphics::DrawState = Default::default();
+        let blend = self.blend.or(inherited_blend);
+        let draw_state = blend.map(|mode| mode.draw_state()).unwrap_or_default();
 
         // for debug: bounding_box
         //model.rgb(1.0, 0.0, 0.0).draw(b);
@@ -452,67 +866,204 @@ impl<I: ImageSize> Sprite<I> {
                 source_rectangle[3],
             ])
             .maybe_src_rect(self.src_rect) // FIXME: check if this affects frame sets
-            .draw(&*self.texture, draw_state, model, b);
+            .draw(texture, &draw_state, model, b);
 
         // for debug: anchor point
         //c.trans(self.position[0], self.position[1]).rect(-5.0, -5.0, 10.0, 10.0).rgb(0.0, 0.0, 1.0).draw(b);
 
         for child in &self.children {
-            child.draw_tinted(transformed, b, c);
+            child.draw_tinted_blended(transformed, b, c, blend);
         }
     }
 
     /// Update the frame delta and act accordingly
     pub fn update(&mut self, dt: f64) {
         if self.frames.is_some() {
-            let mut followup: Option<String> = None;
+            let mut goto: Option<String> = None;
+            let mut hit: Option<usize> = None;
             if let Some(ref frame) = self.frames {
                 self.frame_delta += dt;
-                if self.frame_delta > frame.frame_time {
+                if self.frame_delta > frame.durations[self.frame_idx] {
                     self.frame_delta = 0.0;
                     if self.frame_idx == frame.source.len() - 1 {
-                        if let Some(ref next) = self.frames_followup {
-                            self.frame_idx = 0;
-                            followup = Some(next.clone());
-                        }
-                        if frame.repeat {
-                            self.frame_idx = 0;
+                        match frame.on_end {
+                            Transition::Stop => {}
+                            Transition::Loop => {
+                                self.frame_idx = 0;
+                                hit = Some(self.frame_idx);
+                            }
+                            Transition::Goto(ref next) => {
+                                self.frame_idx = 0;
+                                goto = Some(next.clone());
+                            }
                         }
+                    } else {
+                        self.frame_idx += 1;
+                        hit = Some(self.frame_idx);
                     }
                 }
             }
-            if let Some(next) = followup {
-                self.play(&next, None);
+            if let Some(idx) = hit {
+                if self.frame_hit_marks.contains(&idx) {
+                    self.frame_hit_queue.push(idx);
+                }
+            }
+            if let Some(next) = goto {
+                self.play(&next);
+            }
+        }
+
+        if let Some(mut interp) = self.tween_position.take() {
+            let (value, finished) = interp.update(dt);
+            self.position = value;
+            self.local_transform.set(None);
+            if !finished {
+                self.tween_position = Some(interp);
+            }
+        }
+
+        if let Some(mut interp) = self.tween_scale.take() {
+            let (value, finished) = interp.update(dt);
+            self.scale = value;
+            self.local_transform.set(None);
+            if !finished {
+                self.tween_scale = Some(interp);
+            }
+        }
+
+        if let Some(mut interp) = self.tween_rotation.take() {
+            let (value, finished) = interp.update(dt);
+            self.rotation = value;
+            self.local_transform.set(None);
+            if !finished {
+                self.tween_rotation = Some(interp);
+            }
+        }
+
+        if let Some(mut interp) = self.tween_color.take() {
+            let (value, finished) = interp.update(dt);
+            self.color = [value[0] as f32, value[1] as f32, value[2] as f32];
+            if !finished {
+                self.tween_color = Some(interp);
+            }
+        }
+
+        if let Some(mut interp) = self.tween_opacity.take() {
+            let (value, finished) = interp.update(dt);
+            self.opacity = value as f32;
+            if !finished {
+                self.tween_opacity = Some(interp);
             }
         }
     }
 
-    /// Start an animated frameset
-    pub fn play(&mut self, name: &str, followup: Option<&str>) {
-        if self.frame_sets.contains_key(name) {
-            self.frames = Some(self.frame_sets.get(name).unwrap().clone());
+    /// Start an animated frameset, consulting its `on_enter` transition.
+    ///
+    /// `on_enter: Goto(name)` redirects to another section before playing,
+    /// so a section can act as a pure alias. `Stop` and `Loop` play the
+    /// named section as-is: both enter at its first frame, since they only
+    /// differ in what happens once playback reaches the last one.
+    ///
+    /// A chain of aliases that never reaches a concrete `Stop`/`Loop`
+    /// section (e.g. two `Goto`s pointing at each other) leaves whatever
+    /// was already playing untouched rather than recursing forever.
+    pub fn play(&mut self, name: &str) {
+        self.play_section(name, &mut HashSet::new());
+    }
+
+    fn play_section(&mut self, name: &str, visited: &mut HashSet<String>) {
+        if !visited.insert(name.to_owned()) {
+            return;
         }
-        match followup {
-            None => self.frames_followup = None,
-            Some(next) => self.frames_followup = Some(next.to_owned()),
+        if let Some(section) = self.frame_sets.get(name).cloned() {
+            match section.on_enter {
+                Transition::Goto(ref alias) => {
+                    let alias = alias.clone();
+                    return self.play_section(&alias, visited);
+                }
+                Transition::Stop | Transition::Loop => {
+                    self.enter_section(section);
+                }
+            }
         }
     }
 
-    /// Add an animated frameset
+    /// Make `section` the active frameset, resetting playback to its first
+    /// frame and queuing a frame-hit notification for it if one is armed.
+    fn enter_section(&mut self, section: FrameSet<I>) {
+        self.frames = Some(section);
+        self.frame_idx = 0;
+        self.frame_delta = 0.0;
+        if self.frame_hit_marks.contains(&0) {
+            self.frame_hit_queue.push(0);
+        }
+    }
+
+    /// Set the `on_enter` transition of an existing frameset
+    pub fn set_frameset_on_enter(&mut self, name: &str, on_enter: Transition) {
+        if let Some(frame_set) = self.frame_sets.get_mut(name) {
+            frame_set.on_enter = on_enter;
+        }
+    }
+
+    /// Set the `on_end` transition of an existing frameset
+    pub fn set_frameset_on_end(&mut self, name: &str, on_end: Transition) {
+        if let Some(frame_set) = self.frame_sets.get_mut(name) {
+            frame_set.on_end = on_end;
+        }
+    }
+
+    /// Add an animated frameset with a frame time shared by every frame
     pub fn add_frameset(
         &mut self,
         name: &str,
         repeat: bool,
         frame_time: f64,
         source: Vec<SourceRectangle>,
+    ) {
+        let frames = source.into_iter().map(|rect| (rect, frame_time)).collect();
+        self.add_frameset_timed(name, repeat, frames);
+    }
+
+    /// Add an animated frameset where each frame carries its own duration
+    pub fn add_frameset_timed(
+        &mut self,
+        name: &str,
+        repeat: bool,
+        frames: Vec<(SourceRectangle, f64)>,
+    ) {
+        let mut source = Vec::with_capacity(frames.len());
+        let mut durations = Vec::with_capacity(frames.len());
+        for (rect, duration) in frames {
+            source.push(rect);
+            durations.push(duration);
+        }
+        let textures = vec![None; source.len()];
+        let on_end = if repeat { Transition::Loop } else { Transition::Stop };
+        self.insert_frameset(name, source, durations, textures, Transition::Stop, on_end);
+    }
+
+    /// Build and register a `FrameSet` named `name`, unless one is already
+    /// registered under that name. Shared by `add_frameset_timed` and
+    /// `from_def` so the two ways of building a frameset can't drift.
+    fn insert_frameset(
+        &mut self,
+        name: &str,
+        source: Vec<SourceRectangle>,
+        durations: Vec<f64>,
+        textures: Vec<Option<Rc<I>>>,
+        on_enter: Transition,
+        on_end: Transition,
     ) {
         if !self.frame_sets.contains_key(name) {
             self.frame_sets.insert(
                 name.to_owned(),
                 FrameSet {
-                    repeat: repeat,
-                    frame_time: frame_time,
+                    durations: durations,
                     source: source,
+                    textures: textures,
+                    on_enter: on_enter,
+                    on_end: on_end,
                 },
             );
         }
@@ -555,4 +1106,174 @@ impl<I: ImageSize> Sprite<I> {
             sprite_h,
         ]
     }
+
+    /// Find the topmost visible sprite (self or a descendant) whose local
+    /// texture rectangle contains `point`, given the transform of this
+    /// sprite's parent (pass `identity()` if this sprite is the root).
+    ///
+    /// Respects rotation, skew, anchor, the current frameset's source rect,
+    /// `flip_x`/`flip_y`, and `opacity`; invisible subtrees are skipped.
+    /// Children are tested front-to-back (reverse draw order) so the
+    /// last-drawn, topmost match wins.
+    pub fn hit_test(&self, point: Vec2d, parent_transform: Matrix2d) -> Option<Uuid> {
+        if !self.visible {
+            return None;
+        }
+
+        let transformed = multiply(parent_transform, self.local_transform());
+
+        for child in self.children.iter().rev() {
+            if let Some(hit) = child.hit_test(point, transformed) {
+                return Some(hit);
+            }
+        }
+
+        if self.opacity <= 0.0 {
+            return None;
+        }
+
+        let (source_rectangle, anchor) = self.frame_geometry();
+        let model = self.apply_flip(transformed, source_rectangle, anchor);
+
+        let local = transform_pos(invert_affine(model), point);
+        if local[0] >= -anchor[0]
+            && local[0] <= source_rectangle[2] - anchor[0]
+            && local[1] >= -anchor[1]
+            && local[1] <= source_rectangle[3] - anchor[1]
+        {
+            return Some(self.id);
+        }
+
+        None
+    }
+}
+
+/// A single frame within a `FrameSetDef`, either a source rectangle on the
+/// sprite's own texture or the key of a separate texture to resolve.
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum FrameDef {
+    Rect(SourceRectangle),
+    Texture(String),
+}
+
+/// Declarative counterpart of `FrameSet`.
+///
+/// Timing is expressed as either `fps` or a total `duration`; exactly one
+/// should be given and is converted into a uniform `frame_time`.
+///
+/// `on_enter` defaults to `Stop`; `on_end`, when not given, falls back to
+/// `Loop` or `Stop` according to `repeat`, for compatibility with
+/// definitions written before transitions were configurable here.
+#[derive(Clone, Deserialize)]
+pub struct FrameSetDef {
+    #[serde(default)]
+    pub repeat: bool,
+    #[serde(default)]
+    pub fps: Option<f64>,
+    #[serde(default)]
+    pub duration: Option<f64>,
+    #[serde(default)]
+    pub on_enter: Option<Transition>,
+    #[serde(default)]
+    pub on_end: Option<Transition>,
+    pub frames: Vec<FrameDef>,
+}
+
+impl FrameSetDef {
+    fn frame_time(&self) -> f64 {
+        match self.fps {
+            Some(fps) if fps > 0.0 => 1.0 / fps,
+            _ => match self.duration {
+                Some(duration) if !self.frames.is_empty() => {
+                    duration / self.frames.len() as f64
+                }
+                _ => 0.0,
+            },
+        }
+    }
+}
+
+/// Declarative definition of a `Sprite<I>` and its animation sections,
+/// meant to be deserialized from a JSON5/TOML content file.
+#[derive(Clone, Deserialize)]
+pub struct SpriteDef {
+    pub texture: String,
+    #[serde(default)]
+    pub anchor: Option<[Scalar; 2]>,
+    #[serde(default)]
+    pub position: Option<[Scalar; 2]>,
+    #[serde(default)]
+    pub scale: Option<[Scalar; 2]>,
+    #[serde(default)]
+    pub sections: HashMap<String, FrameSetDef>,
+    #[serde(default)]
+    pub start_at: Option<String>,
+    #[serde(default)]
+    pub children: Vec<SpriteDef>,
+}
+
+impl<I: ImageSize> Sprite<I> {
+    /// Build a sprite (and its framesets) from a declarative `SpriteDef`.
+    ///
+    /// Textures can't be deserialized directly, so `texture_resolver` maps
+    /// the texture keys named in the definition to the actual `Rc<I>`
+    /// loaded by the caller.
+    pub fn from_def<F>(def: &SpriteDef, texture_resolver: &mut F) -> Sprite<I>
+    where
+        F: FnMut(&str) -> Rc<I>,
+    {
+        let mut sprite = Sprite::from_texture(texture_resolver(&def.texture));
+
+        if let Some(anchor) = def.anchor {
+            sprite.set_anchor(anchor[0], anchor[1]);
+        }
+        if let Some(position) = def.position {
+            sprite.set_position(position[0], position[1]);
+        }
+        if let Some(scale) = def.scale {
+            sprite.set_scale(scale[0], scale[1]);
+        }
+
+        for (name, section) in &def.sections {
+            let frame_time = section.frame_time();
+            let mut source = Vec::with_capacity(section.frames.len());
+            let mut textures = Vec::with_capacity(section.frames.len());
+            for frame in &section.frames {
+                match *frame {
+                    FrameDef::Rect(rect) => {
+                        source.push(rect);
+                        textures.push(None);
+                    }
+                    FrameDef::Texture(ref key) => {
+                        let texture = texture_resolver(key);
+                        let (w, h) = texture.get_size();
+                        source.push([0.0, 0.0, w as f64, h as f64]);
+                        textures.push(Some(texture));
+                    }
+                }
+            }
+            let durations = vec![frame_time; source.len()];
+            let on_enter = section.on_enter.clone().unwrap_or(Transition::Stop);
+            let on_end = section.on_end.clone().unwrap_or_else(|| {
+                if section.repeat {
+                    Transition::Loop
+                } else {
+                    Transition::Stop
+                }
+            });
+            sprite.insert_frameset(name, source, durations, textures, on_enter, on_end);
+        }
+
+        for child_def in &def.children {
+            let child = Sprite::from_def(child_def, texture_resolver);
+            sprite.add_child(child);
+        }
+
+        if let Some(ref start_at) = def.start_at {
+            sprite.play(start_at);
+        }
+
+        sprite
+    }
 }